@@ -0,0 +1,166 @@
+#![no_std]
+#![feature(type_alias_impl_trait)]
+
+//! USB DFU (Device Firmware Upgrade) runtime class for `embassy-usb`.
+//!
+//! Registers just the interface and functional descriptor the DFU 1.1 runtime
+//! mode needs (no data endpoints: firmware transfer happens after the device
+//! detaches and re-enumerates in the bootloader), and answers `DFU_DETACH`/
+//! `DFU_GETSTATUS` so a host tool like `dfu-util` can ask the application to
+//! drop into its bootloader without a separate programmer.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_usb::control::{OutResponse, Recipient, Request, RequestType};
+use embassy_usb::driver::Driver;
+use embassy_usb::types::InterfaceNumber;
+use embassy_usb::{Handler, UsbDeviceBuilder};
+
+const USB_CLASS_APPLICATION_SPECIFIC: u8 = 0xfe;
+const USB_SUBCLASS_DFU: u8 = 0x01;
+const USB_PROTOCOL_DFU_RUNTIME: u8 = 0x01;
+
+const DFU_DESC_DESCTYPE_FUNCTIONAL: u8 = 0x21;
+
+const DFU_REQ_DETACH: u8 = 0x00;
+const DFU_REQ_GETSTATUS: u8 = 0x03;
+
+/// `bmAttributes` bits of the DFU functional descriptor (DFU 1.1 table 4.2).
+const DFU_ATTR_WILL_DETACH: u8 = 0x08;
+const DFU_ATTR_MANIFESTATION_TOLERANT: u8 = 0x04;
+// `bitCanDnload`/`bitCanUpload` are deliberately left unset: this class only
+// implements the DFU *runtime* interface (DFU 1.1 section 4, "runtime ->
+// dfuMode" transition). It has no data endpoints and answers neither
+// `DFU_DNLOAD` nor `DFU_UPLOAD` itself; transfer happens against the
+// bootloader's own DFU interface after `DFU_DETACH` causes re-enumeration, so
+// advertising the bits here would claim a capability this interface doesn't
+// have. Host tools that need to see them (e.g. `dfu-util -l`) should be
+// pointed at the bootloader's interface once it re-enumerates.
+
+/// `bState` values `DFU_GETSTATUS` reports (DFU 1.1 table A.1.1); only the two
+/// runtime-mode states are relevant here, since this class never enters `dfuIDLE`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum DfuState {
+    AppIdle = 0,
+    AppDetach = 1,
+}
+
+/// User-provided callback for `DFU_DETACH`, analogous to `DeviceStateHandler`.
+///
+/// Typically sets a magic value in a RAM region the bootloader checks on reset,
+/// then resets the device so it re-enumerates in DFU mode.
+pub trait DfuDetachHandler {
+    /// Called when the host issues `DFU_DETACH`.
+    fn detach(&self);
+}
+
+/// Handles the DFU class control requests for one [`DfuRuntimeClass`] interface.
+pub struct DfuRuntimeHandler<'d> {
+    iface: InterfaceNumber,
+    detach_handler: Option<&'d dyn DfuDetachHandler>,
+    detached: AtomicBool,
+}
+
+impl<'d> Handler for DfuRuntimeHandler<'d> {
+    fn control_out(&mut self, req: Request, _data: &[u8]) -> Option<OutResponse> {
+        if req.request_type != RequestType::Class
+            || req.recipient != Recipient::Interface
+            || req.index != u8::from(self.iface) as u16
+        {
+            return None;
+        }
+
+        match req.request {
+            DFU_REQ_DETACH => {
+                self.detached.store(true, Ordering::Relaxed);
+                if let Some(handler) = self.detach_handler {
+                    handler.detach();
+                }
+                Some(OutResponse::Accepted)
+            }
+            _ => None,
+        }
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<usize> {
+        if req.request_type != RequestType::Class
+            || req.recipient != Recipient::Interface
+            || req.index != u8::from(self.iface) as u16
+        {
+            return None;
+        }
+
+        match req.request {
+            DFU_REQ_GETSTATUS => {
+                let state = if self.detached.load(Ordering::Relaxed) {
+                    DfuState::AppDetach
+                } else {
+                    DfuState::AppIdle
+                };
+                buf[0] = 0x00; // bStatus: OK
+                buf[1..4].copy_from_slice(&[0, 0, 0]); // bwPollTimeout: poll again immediately
+                buf[4] = state as u8;
+                buf[5] = 0; // iString
+                Some(6)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A USB DFU runtime class instance: one interface, no endpoints.
+///
+/// Registers itself on `builder` alongside other classes (e.g. `HidClass`), so
+/// firmware can offer DFU entry on the same composite device it already exposes.
+pub struct DfuRuntimeClass;
+
+impl DfuRuntimeClass {
+    /// Registers the class on `builder`, returning the [`DfuRuntimeHandler`] the
+    /// caller must keep alive and pass to `builder.handler()`.
+    ///
+    /// `detach_timeout_ms` is how long the host should wait after `DFU_DETACH`
+    /// before giving up on the device re-enumerating; `transfer_size` is the
+    /// largest control transfer the bootloader's `DFU_DNLOAD`/`DFU_UPLOAD` will
+    /// accept, advertised here so host tools can size their requests up front.
+    pub fn new<'d, D: Driver<'d>>(
+        builder: &mut UsbDeviceBuilder<'d, D>,
+        detach_handler: Option<&'d dyn DfuDetachHandler>,
+        detach_timeout_ms: u16,
+        transfer_size: u16,
+    ) -> DfuRuntimeHandler<'d> {
+        let iface = builder.alloc_interface_association(
+            1,
+            USB_CLASS_APPLICATION_SPECIFIC,
+            USB_SUBCLASS_DFU,
+            USB_PROTOCOL_DFU_RUNTIME,
+        );
+
+        let writer = builder.config_descriptor();
+        writer.interface(
+            iface,
+            0,
+            USB_CLASS_APPLICATION_SPECIFIC,
+            USB_SUBCLASS_DFU,
+            USB_PROTOCOL_DFU_RUNTIME,
+        );
+        writer.write(
+            DFU_DESC_DESCTYPE_FUNCTIONAL,
+            &[
+                DFU_ATTR_WILL_DETACH | DFU_ATTR_MANIFESTATION_TOLERANT,
+                detach_timeout_ms as u8,
+                (detach_timeout_ms >> 8) as u8,
+                transfer_size as u8,
+                (transfer_size >> 8) as u8,
+                0x10,
+                0x01, // bcdDFUVersion 1.10
+            ],
+        );
+
+        DfuRuntimeHandler {
+            iface,
+            detach_handler,
+            detached: AtomicBool::new(false),
+        }
+    }
+}
@@ -0,0 +1,401 @@
+#![no_std]
+#![feature(type_alias_impl_trait)]
+
+//! HID class implementation for `embassy-usb`.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use embassy::time::Duration;
+use embassy_usb::control::{OutResponse, Recipient, Request, RequestType};
+use embassy_usb::driver::{Driver, EndpointError, EndpointIn, EndpointOut, EndpointType};
+use embassy_usb::types::InterfaceNumber;
+use embassy_usb::{Handler, UsbDeviceBuilder};
+
+const USB_CLASS_HID: u8 = 0x03;
+const USB_SUBCLASS_NONE: u8 = 0x00;
+const USB_PROTOCOL_NONE: u8 = 0x00;
+
+const HID_DESC_DESCTYPE_HID: u8 = 0x21;
+const HID_DESC_DESCTYPE_HID_REPORT: u8 = 0x22;
+
+const EP_ATTR_INTERRUPT: u8 = 0x03;
+
+const HID_REQ_GET_REPORT: u8 = 0x01;
+const HID_REQ_GET_IDLE: u8 = 0x02;
+const HID_REQ_GET_PROTOCOL: u8 = 0x03;
+const HID_REQ_SET_REPORT: u8 = 0x09;
+const HID_REQ_SET_IDLE: u8 = 0x0a;
+const HID_REQ_SET_PROTOCOL: u8 = 0x0b;
+
+/// Standard `bRequest` for `GET_DESCRIPTOR` (USB 2.0 table 9-4), used here to
+/// serve the HID report descriptor off the interface recipient.
+const REQ_GET_DESCRIPTOR: u8 = 0x06;
+
+/// The HID protocol an interface is currently speaking (HID 1.11 7.2.5/7.2.6).
+///
+/// Boot keyboards/mice fall back to `Boot` so they work in a PC BIOS, which only
+/// understands the fixed 8-byte boot report layout; `Report` is everything else.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HidProtocol {
+    Boot,
+    #[default]
+    Report,
+}
+
+impl HidProtocol {
+    fn from_value(value: u16) -> Option<Self> {
+        match value {
+            0 => Some(HidProtocol::Boot),
+            1 => Some(HidProtocol::Report),
+            _ => None,
+        }
+    }
+
+    fn to_value(self) -> u8 {
+        match self {
+            HidProtocol::Boot => 0,
+            HidProtocol::Report => 1,
+        }
+    }
+}
+
+/// Identifies a HID report for the purposes of `GET_REPORT`/`SET_REPORT`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ReportId {
+    In(u8),
+    Out(u8),
+    Feature(u8),
+}
+
+/// User-provided callbacks for requests the `HidClass` can't answer by itself.
+pub trait RequestHandler {
+    /// Called on a `GET_REPORT` request; return `None` to stall.
+    fn get_report(&self, id: ReportId, buf: &mut [u8]) -> Option<usize> {
+        let _ = (id, buf);
+        None
+    }
+
+    /// Called on a `SET_REPORT` request.
+    fn set_report(&self, id: ReportId, data: &[u8]) -> OutResponse {
+        let _ = (id, data);
+        OutResponse::Rejected
+    }
+
+    /// Called on a `SET_IDLE` request.
+    fn set_idle(&self, id: Option<ReportId>, dur: Duration) {
+        let _ = (id, dur);
+    }
+
+    /// Called on a `GET_IDLE` request; return `None` to stall.
+    fn get_idle(&self, id: Option<ReportId>) -> Option<Duration> {
+        let _ = id;
+        None
+    }
+
+    /// Called on a `SET_PROTOCOL` request, after the new protocol has already
+    /// been stored, so [`HidWriter::protocol`] reports it by then.
+    fn set_protocol(&self, protocol: HidProtocol) {
+        let _ = protocol;
+    }
+
+    /// Called once, while the class is being built, to seed the protocol a boot
+    /// keyboard/mouse should start in; the default (`Report`) is correct for
+    /// anything that isn't a boot-capable device.
+    fn get_protocol(&self) -> HidProtocol {
+        HidProtocol::default()
+    }
+}
+
+impl ReportId {
+    fn from_value(value: u16) -> Option<Self> {
+        let id = value as u8;
+        match value >> 8 {
+            1 => Some(ReportId::In(id)),
+            2 => Some(ReportId::Out(id)),
+            3 => Some(ReportId::Feature(id)),
+            _ => None,
+        }
+    }
+
+    /// Decodes the report ID out of a `GET_IDLE`/`SET_IDLE` `wValue` (HID 1.11
+    /// 7.2.3/7.2.4), which unlike `GET_REPORT`/`SET_REPORT` carries only a report
+    /// ID in the low byte and no type tag (the high byte is the idle duration for
+    /// `SET_IDLE`, reserved for `GET_IDLE`). ID 0 means "all reports", hence `None`.
+    fn from_idle_value(value: u16) -> Option<Self> {
+        match value as u8 {
+            0 => None,
+            id => Some(ReportId::In(id)),
+        }
+    }
+}
+
+/// Routes `GET_REPORT`/`SET_REPORT`/`GET_IDLE`/`SET_IDLE`/`GET_PROTOCOL`/`SET_PROTOCOL`
+/// control requests targeting one [`HidClass`]'s interface to its [`RequestHandler`].
+pub struct HidHandler<'d> {
+    iface: InterfaceNumber,
+    request_handler: Option<&'d dyn RequestHandler>,
+    protocol: &'d AtomicU8,
+    report_descriptor: &'static [u8],
+}
+
+impl<'d> Handler for HidHandler<'d> {
+    fn control_out(&mut self, req: Request, data: &[u8]) -> Option<OutResponse> {
+        if req.request_type != RequestType::Class
+            || req.recipient != Recipient::Interface
+            || req.index != u8::from(self.iface) as u16
+        {
+            return None;
+        }
+
+        match req.request {
+            HID_REQ_SET_REPORT => {
+                let id = ReportId::from_value(req.value)?;
+                Some(self.request_handler?.set_report(id, data))
+            }
+            HID_REQ_SET_IDLE => {
+                let id = ReportId::from_idle_value(req.value);
+                let dur = Duration::from_millis(u64::from(req.value >> 8) * 4);
+                self.request_handler?.set_idle(id, dur);
+                Some(OutResponse::Accepted)
+            }
+            HID_REQ_SET_PROTOCOL => {
+                let protocol = HidProtocol::from_value(req.value)?;
+                self.protocol.store(protocol.to_value(), Ordering::Relaxed);
+                if let Some(handler) = self.request_handler {
+                    handler.set_protocol(protocol);
+                }
+                Some(OutResponse::Accepted)
+            }
+            _ => None,
+        }
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<usize> {
+        if req.recipient != Recipient::Interface || req.index != u8::from(self.iface) as u16 {
+            return None;
+        }
+
+        // A standard GET_DESCRIPTOR(HID report) targets the interface, not the
+        // device, so it never reaches `UsbDevice::handle_get_descriptor` and has
+        // to be served here instead.
+        if req.request_type == RequestType::Standard
+            && req.request == REQ_GET_DESCRIPTOR
+            && (req.value >> 8) as u8 == HID_DESC_DESCTYPE_HID_REPORT
+        {
+            // `control_buf` must be sized for the whole report descriptor: unlike
+            // the fixed-size boot report, there's no sane "partial" report
+            // descriptor to hand back, so stall rather than serve a truncated one.
+            if self.report_descriptor.len() > buf.len() {
+                return None;
+            }
+            let len = self.report_descriptor.len();
+            buf[..len].copy_from_slice(&self.report_descriptor[..len]);
+            return Some(len);
+        }
+
+        if req.request_type != RequestType::Class {
+            return None;
+        }
+
+        match req.request {
+            HID_REQ_GET_REPORT => {
+                let id = ReportId::from_value(req.value)?;
+                self.request_handler?.get_report(id, buf)
+            }
+            HID_REQ_GET_IDLE => {
+                let id = ReportId::from_idle_value(req.value);
+                let dur = self.request_handler?.get_idle(id)?;
+                buf[0] = (dur.as_millis() / 4) as u8;
+                Some(1)
+            }
+            HID_REQ_GET_PROTOCOL => {
+                buf[0] = self.protocol.load(Ordering::Relaxed);
+                Some(1)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Buffers the `HidClass` needs, parameterized by whether an OUT endpoint is
+/// in use (`OUT`, 0 or 1).
+pub struct State<const OUT: usize> {
+    protocol: AtomicU8,
+}
+
+impl<const OUT: usize> State<OUT> {
+    pub fn new() -> Self {
+        State {
+            protocol: AtomicU8::new(HidProtocol::default().to_value()),
+        }
+    }
+}
+
+/// A HID class instance: one interface, an IN endpoint, and optionally an OUT endpoint.
+pub struct HidClass<'d, D: Driver<'d>> {
+    ep_in: D::EndpointIn,
+    ep_out: Option<D::EndpointOut>,
+    protocol: &'d AtomicU8,
+}
+
+impl<'d, D: Driver<'d>> HidClass<'d, D> {
+    /// Registers a HID class with only an IN (report) endpoint.
+    pub fn new(
+        builder: &mut UsbDeviceBuilder<'d, D>,
+        state: &'d mut State<0>,
+        report_descriptor: &'static [u8],
+        request_handler: Option<&'d dyn RequestHandler>,
+        poll_ms: u8,
+        max_packet_size: u16,
+    ) -> (Self, HidHandler<'d>) {
+        Self::build(builder, state, report_descriptor, request_handler, false, poll_ms, max_packet_size)
+    }
+
+    /// Registers a HID class with both an IN (report) and OUT (host-to-device) endpoint.
+    pub fn with_output_ep(
+        builder: &mut UsbDeviceBuilder<'d, D>,
+        state: &'d mut State<1>,
+        report_descriptor: &'static [u8],
+        request_handler: Option<&'d dyn RequestHandler>,
+        poll_ms: u8,
+        max_packet_size: u16,
+    ) -> (Self, HidHandler<'d>) {
+        Self::build(builder, state, report_descriptor, request_handler, true, poll_ms, max_packet_size)
+    }
+
+    fn build<const OUT: usize>(
+        builder: &mut UsbDeviceBuilder<'d, D>,
+        state: &'d mut State<OUT>,
+        report_descriptor: &'static [u8],
+        request_handler: Option<&'d dyn RequestHandler>,
+        with_out: bool,
+        poll_ms: u8,
+        max_packet_size: u16,
+    ) -> (Self, HidHandler<'d>) {
+        if let Some(handler) = request_handler {
+            state.protocol.store(handler.get_protocol().to_value(), Ordering::Relaxed);
+        }
+
+        let iface = builder.alloc_interface_association(1, USB_CLASS_HID, USB_SUBCLASS_NONE, USB_PROTOCOL_NONE);
+
+        // Allocate every endpoint before writing any endpoint descriptor: the
+        // descriptor needs the address the driver hands back.
+        let ep_in = builder
+            .driver()
+            .alloc_endpoint_in(EndpointType::Interrupt, max_packet_size, poll_ms)
+            .expect("failed to allocate HID IN endpoint");
+        let ep_out = with_out.then(|| {
+            builder
+                .driver()
+                .alloc_endpoint_out(EndpointType::Interrupt, max_packet_size, poll_ms)
+                .expect("failed to allocate HID OUT endpoint")
+        });
+
+        let writer = builder.config_descriptor();
+        writer.interface(iface, 0, USB_CLASS_HID, USB_SUBCLASS_NONE, USB_PROTOCOL_NONE);
+        // The HID descriptor (HID 1.11 6.2.1) is its own descriptor type, not a
+        // CS_INTERFACE wrapper around one: `write_cs_interface` would prefix it
+        // with a bogus subtype byte that shifts every following field out of place.
+        writer.write(
+            HID_DESC_DESCTYPE_HID,
+            &[
+                0x11,
+                0x01, // HID 1.11
+                0x00, // country code
+                0x01, // num descriptors
+                HID_DESC_DESCTYPE_HID_REPORT,
+                report_descriptor.len() as u8,
+                (report_descriptor.len() >> 8) as u8,
+            ],
+        );
+        writer.endpoint(ep_in.address().into(), EP_ATTR_INTERRUPT, max_packet_size, poll_ms);
+        if let Some(ep_out) = &ep_out {
+            writer.endpoint(ep_out.address().into(), EP_ATTR_INTERRUPT, max_packet_size, poll_ms);
+        }
+
+        (
+            HidClass {
+                ep_in,
+                ep_out,
+                protocol: &state.protocol,
+            },
+            HidHandler {
+                iface,
+                request_handler,
+                protocol: &state.protocol,
+                report_descriptor,
+            },
+        )
+    }
+
+    /// Splits the class into its writer (IN) and reader (OUT) halves so they can
+    /// be driven from independent tasks/futures.
+    pub fn split(self) -> (HidWriter<'d, D>, HidReader<'d, D>) {
+        (
+            HidWriter {
+                ep_in: self.ep_in,
+                protocol: self.protocol,
+            },
+            HidReader { ep_out: self.ep_out },
+        )
+    }
+}
+
+/// The writable (IN) half of a split [`HidClass`].
+pub struct HidWriter<'d, D: Driver<'d>> {
+    ep_in: D::EndpointIn,
+    protocol: &'d AtomicU8,
+}
+
+impl<'d, D: Driver<'d>> HidWriter<'d, D> {
+    /// Serializes `report` with `ssmarshal`/`usbd-hid` conventions and sends it.
+    pub async fn serialize<R: usbd_hid::descriptor::AsInputReport>(
+        &mut self,
+        report: &R,
+    ) -> Result<(), EndpointError> {
+        let mut buf = [0u8; 64];
+        let size = ssmarshal::serialize(&mut buf, report).map_err(|_| EndpointError::BufferOverflow)?;
+        self.ep_in.write(&buf[..size]).await
+    }
+
+    /// The protocol the host last selected with `SET_PROTOCOL`, so firmware can
+    /// switch between the fixed boot report layout and the richer report-protocol
+    /// descriptor while serializing.
+    pub fn protocol(&self) -> HidProtocol {
+        match self.protocol.load(Ordering::Relaxed) {
+            0 => HidProtocol::Boot,
+            _ => HidProtocol::Report,
+        }
+    }
+}
+
+/// The readable (OUT) half of a split [`HidClass`].
+pub struct HidReader<'d, D: Driver<'d>> {
+    ep_out: Option<D::EndpointOut>,
+}
+
+impl<'d, D: Driver<'d>> HidReader<'d, D> {
+    /// Drives `SET_REPORT` style OUT reports to `request_handler` until the bus
+    /// disconnects; `reboot_after_report` ends the loop after the first report
+    /// if set, useful for devices that re-enumerate on their own.
+    pub async fn run(&mut self, reboot_after_report: bool, request_handler: &dyn RequestHandler) {
+        let Some(ep_out) = self.ep_out.as_mut() else {
+            return;
+        };
+        loop {
+            let mut buf = [0u8; 64];
+            match ep_out.read(&mut buf).await {
+                Ok(n) => {
+                    request_handler.set_report(ReportId::Out(0), &buf[..n]);
+                    if reboot_after_report {
+                        return;
+                    }
+                }
+                Err(EndpointError::Disabled) => return,
+                Err(_) => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,334 @@
+#![no_std]
+#![feature(type_alias_impl_trait)]
+
+//! CDC-ACM (virtual serial port) class implementation for `embassy-usb`.
+//!
+//! Registers a communications interface (with a notification endpoint, unused
+//! for anything but link state in most hosts) and a data interface with the
+//! bulk IN/OUT endpoints firmware actually reads and writes through.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+
+use embassy_usb::control::{OutResponse, Recipient, Request, RequestType};
+use embassy_usb::driver::{Driver, EndpointError, EndpointIn, EndpointOut, EndpointType};
+use embassy_usb::types::InterfaceNumber;
+use embassy_usb::{Handler, UsbDeviceBuilder};
+
+const USB_CLASS_CDC: u8 = 0x02;
+const USB_SUBCLASS_ACM: u8 = 0x02;
+const USB_PROTOCOL_NONE: u8 = 0x00;
+const USB_CLASS_CDC_DATA: u8 = 0x0a;
+
+const CDC_DESC_SUBTYPE_HEADER: u8 = 0x00;
+const CDC_DESC_SUBTYPE_CALL_MANAGEMENT: u8 = 0x01;
+const CDC_DESC_SUBTYPE_ACM: u8 = 0x02;
+const CDC_DESC_SUBTYPE_UNION: u8 = 0x06;
+
+const REQ_SEND_ENCAPSULATED_COMMAND: u8 = 0x00;
+const REQ_GET_ENCAPSULATED_RESPONSE: u8 = 0x01;
+const REQ_SET_LINE_CODING: u8 = 0x20;
+const REQ_GET_LINE_CODING: u8 = 0x21;
+const REQ_SET_CONTROL_LINE_STATE: u8 = 0x22;
+
+/// The `SET_LINE_CODING`/`GET_LINE_CODING` payload (CDC PSTN120 6.2.13).
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LineCoding {
+    pub data_rate: u32,
+    pub stop_bits: u8,
+    pub parity_type: u8,
+    pub data_bits: u8,
+}
+
+impl Default for LineCoding {
+    fn default() -> Self {
+        LineCoding {
+            data_rate: 115200,
+            stop_bits: 0,
+            parity_type: 0,
+            data_bits: 8,
+        }
+    }
+}
+
+impl LineCoding {
+    fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < 7 {
+            return None;
+        }
+        Some(LineCoding {
+            data_rate: u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+            stop_bits: data[4],
+            parity_type: data[5],
+            data_bits: data[6],
+        })
+    }
+
+    fn to_bytes(self) -> [u8; 7] {
+        let mut buf = [0u8; 7];
+        buf[0..4].copy_from_slice(&self.data_rate.to_le_bytes());
+        buf[4] = self.stop_bits;
+        buf[5] = self.parity_type;
+        buf[6] = self.data_bits;
+        buf
+    }
+}
+
+/// Tracks the line state the host last negotiated over the control interface.
+///
+/// Shared between the [`CdcAcmClass`]'s [`Handler`] and its split halves so
+/// firmware can poll DTR/RTS (e.g. to detect a terminal opening the port).
+/// `LineCoding`'s fields are each stored in their own atomic, since the
+/// handler only ever gets a shared `&ControlState`.
+struct ControlState {
+    comm_if: InterfaceNumber,
+    line_coding_rate: AtomicU32,
+    line_coding_stop_bits: AtomicU8,
+    line_coding_parity_type: AtomicU8,
+    line_coding_data_bits: AtomicU8,
+    dtr: AtomicBool,
+    rts: AtomicBool,
+}
+
+impl ControlState {
+    fn line_coding(&self) -> LineCoding {
+        LineCoding {
+            data_rate: self.line_coding_rate.load(Ordering::Relaxed),
+            stop_bits: self.line_coding_stop_bits.load(Ordering::Relaxed),
+            parity_type: self.line_coding_parity_type.load(Ordering::Relaxed),
+            data_bits: self.line_coding_data_bits.load(Ordering::Relaxed),
+        }
+    }
+
+    fn set_line_coding(&self, coding: LineCoding) {
+        self.line_coding_rate.store(coding.data_rate, Ordering::Relaxed);
+        self.line_coding_stop_bits.store(coding.stop_bits, Ordering::Relaxed);
+        self.line_coding_parity_type.store(coding.parity_type, Ordering::Relaxed);
+        self.line_coding_data_bits.store(coding.data_bits, Ordering::Relaxed);
+    }
+}
+
+/// Handles the CDC class control requests for one [`CdcAcmClass`].
+pub struct CdcAcmHandler<'d> {
+    state: &'d ControlState,
+}
+
+impl<'d> Handler for CdcAcmHandler<'d> {
+    fn control_out(&mut self, req: Request, data: &[u8]) -> Option<OutResponse> {
+        if req.request_type != RequestType::Class || req.recipient != Recipient::Interface {
+            return None;
+        }
+        if req.index != u8::from(self.state.comm_if) as u16 {
+            return None;
+        }
+
+        match req.request {
+            REQ_SET_LINE_CODING => {
+                if let Some(coding) = LineCoding::from_bytes(data) {
+                    self.state.set_line_coding(coding);
+                }
+                Some(OutResponse::Accepted)
+            }
+            REQ_SET_CONTROL_LINE_STATE => {
+                self.state.dtr.store(req.value & 0x1 != 0, Ordering::Relaxed);
+                self.state.rts.store(req.value & 0x2 != 0, Ordering::Relaxed);
+                Some(OutResponse::Accepted)
+            }
+            REQ_SEND_ENCAPSULATED_COMMAND => Some(OutResponse::Accepted),
+            _ => Some(OutResponse::Rejected),
+        }
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<usize> {
+        if req.request_type != RequestType::Class || req.recipient != Recipient::Interface {
+            return None;
+        }
+        if req.index != u8::from(self.state.comm_if) as u16 {
+            return None;
+        }
+
+        match req.request {
+            REQ_GET_LINE_CODING => {
+                let bytes = self.state.line_coding().to_bytes();
+                buf[..bytes.len()].copy_from_slice(&bytes);
+                Some(bytes.len())
+            }
+            REQ_GET_ENCAPSULATED_RESPONSE => Some(0),
+            _ => None,
+        }
+    }
+}
+
+/// A CDC-ACM (virtual serial port) class instance.
+///
+/// Registers a CDC communications interface (IAD, header/call-management/ACM/union
+/// functional descriptors, and a notification endpoint) plus a CDC data interface
+/// carrying the bulk IN/OUT endpoints, mirroring `embassy-usb-hid`'s `HidClass`.
+pub struct CdcAcmClass<'d, D: Driver<'d>> {
+    ep_in: D::EndpointIn,
+    ep_out: D::EndpointOut,
+    // Kept alive so the notification endpoint stays allocated on the peripheral;
+    // nothing is ever written to it (see the comment where its descriptor is emitted).
+    _ep_notif: D::EndpointIn,
+    state: &'d ControlState,
+}
+
+impl<'d, D: Driver<'d>> CdcAcmClass<'d, D> {
+    /// Registers the class on `builder`, returning it and the [`CdcAcmHandler`]
+    /// the caller must keep alive and pass to `builder.handler()`.
+    pub fn new(
+        builder: &mut UsbDeviceBuilder<'d, D>,
+        state: &'d mut CdcAcmState,
+        max_packet_size: u16,
+    ) -> (Self, CdcAcmHandler<'d>) {
+        let comm_if = builder.alloc_interface_association(2, USB_CLASS_CDC, USB_SUBCLASS_ACM, USB_PROTOCOL_NONE);
+        let data_if = builder.alloc_interface();
+
+        state.inner.comm_if = comm_if;
+
+        // Allocate every endpoint before writing any endpoint descriptor: the
+        // descriptor needs the address the driver actually assigned.
+        const EP_ATTR_INTERRUPT: u8 = 0x03;
+        const EP_ATTR_BULK: u8 = 0x02;
+        let ep_notif = builder
+            .driver()
+            .alloc_endpoint_in(EndpointType::Interrupt, 8, 255)
+            .expect("failed to allocate CDC-ACM notification endpoint");
+        let ep_out = builder
+            .driver()
+            .alloc_endpoint_out(EndpointType::Bulk, max_packet_size, 0)
+            .expect("failed to allocate CDC-ACM OUT endpoint");
+        let ep_in = builder
+            .driver()
+            .alloc_endpoint_in(EndpointType::Bulk, max_packet_size, 0)
+            .expect("failed to allocate CDC-ACM IN endpoint");
+
+        let writer = builder.config_descriptor();
+
+        writer.interface(comm_if, 0, USB_CLASS_CDC, USB_SUBCLASS_ACM, USB_PROTOCOL_NONE);
+        writer.write_cs_interface(CDC_DESC_SUBTYPE_HEADER, &[0x10, 0x01]); // CDC 1.10
+        writer.write_cs_interface(
+            CDC_DESC_SUBTYPE_CALL_MANAGEMENT,
+            &[0x00, u8::from(data_if)],
+        );
+        writer.write_cs_interface(CDC_DESC_SUBTYPE_ACM, &[0x02]); // supports SET/GET_LINE_CODING, SET_CONTROL_LINE_STATE
+        writer.write_cs_interface(CDC_DESC_SUBTYPE_UNION, &[u8::from(comm_if), u8::from(data_if)]);
+        // Notification endpoint: most hosts never poll it, but it's required by
+        // the CDC spec and some enumerate more happily with it present.
+        writer.endpoint(ep_notif.address().into(), EP_ATTR_INTERRUPT, 8, 255);
+
+        writer.interface(data_if, 0, USB_CLASS_CDC_DATA, 0x00, USB_PROTOCOL_NONE);
+        writer.endpoint(ep_out.address().into(), EP_ATTR_BULK, max_packet_size, 0);
+        writer.endpoint(ep_in.address().into(), EP_ATTR_BULK, max_packet_size, 0);
+
+        (
+            CdcAcmClass {
+                ep_in,
+                ep_out,
+                _ep_notif: ep_notif,
+                state: &state.inner,
+            },
+            CdcAcmHandler { state: &state.inner },
+        )
+    }
+
+    /// Splits the class into its writer (TX) and reader (RX) halves, mirroring
+    /// `HidClass::split`.
+    pub fn split(self) -> (CdcAcmSender<'d, D>, CdcAcmReceiver<'d, D>) {
+        (
+            CdcAcmSender {
+                ep_in: self.ep_in,
+                state: self.state,
+            },
+            CdcAcmReceiver {
+                ep_out: self.ep_out,
+                state: self.state,
+            },
+        )
+    }
+
+    /// The communications interface's number, i.e. the first of the two interfaces
+    /// the class's IAD associates together.
+    pub fn first_interface(&self) -> InterfaceNumber {
+        self.state.comm_if
+    }
+
+    /// Whether the host has asserted DTR (i.e. a terminal is open on the port).
+    pub fn dtr(&self) -> bool {
+        self.state.dtr.load(Ordering::Relaxed)
+    }
+
+    /// Whether the host has asserted RTS.
+    pub fn rts(&self) -> bool {
+        self.state.rts.load(Ordering::Relaxed)
+    }
+
+    /// Reads one packet (up to `max_packet_size` bytes) from the host.
+    pub async fn read_packet(&mut self, data: &mut [u8]) -> Result<usize, EndpointError> {
+        self.ep_out.read(data).await
+    }
+
+    /// Writes one packet (up to `max_packet_size` bytes) to the host.
+    pub async fn write_packet(&mut self, data: &[u8]) -> Result<(), EndpointError> {
+        self.ep_in.write(data).await
+    }
+}
+
+/// The writable (TX) half of a split [`CdcAcmClass`].
+pub struct CdcAcmSender<'d, D: Driver<'d>> {
+    ep_in: D::EndpointIn,
+    state: &'d ControlState,
+}
+
+impl<'d, D: Driver<'d>> CdcAcmSender<'d, D> {
+    /// Writes one packet (up to `max_packet_size` bytes) to the host.
+    pub async fn write_packet(&mut self, data: &[u8]) -> Result<(), EndpointError> {
+        self.ep_in.write(data).await
+    }
+
+    /// Whether the host has asserted DTR.
+    pub fn dtr(&self) -> bool {
+        self.state.dtr.load(Ordering::Relaxed)
+    }
+}
+
+/// The readable (RX) half of a split [`CdcAcmClass`].
+pub struct CdcAcmReceiver<'d, D: Driver<'d>> {
+    ep_out: D::EndpointOut,
+    state: &'d ControlState,
+}
+
+impl<'d, D: Driver<'d>> CdcAcmReceiver<'d, D> {
+    /// Reads one packet (up to `max_packet_size` bytes) from the host.
+    pub async fn read_packet(&mut self, data: &mut [u8]) -> Result<usize, EndpointError> {
+        self.ep_out.read(data).await
+    }
+
+    /// Whether the host has asserted RTS.
+    pub fn rts(&self) -> bool {
+        self.state.rts.load(Ordering::Relaxed)
+    }
+}
+
+/// Storage for a [`CdcAcmClass`]'s line state, owned by the caller for the
+/// lifetime of the device (mirrors `embassy-usb-hid`'s `State`).
+pub struct CdcAcmState {
+    inner: ControlState,
+}
+
+impl CdcAcmState {
+    pub fn new() -> Self {
+        let default = LineCoding::default();
+        CdcAcmState {
+            inner: ControlState {
+                comm_if: InterfaceNumber::default(),
+                line_coding_rate: AtomicU32::new(default.data_rate),
+                line_coding_stop_bits: AtomicU8::new(default.stop_bits),
+                line_coding_parity_type: AtomicU8::new(default.parity_type),
+                line_coding_data_bits: AtomicU8::new(default.data_bits),
+                dtr: AtomicBool::new(false),
+                rts: AtomicBool::new(false),
+            },
+        }
+    }
+}
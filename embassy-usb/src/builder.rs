@@ -0,0 +1,257 @@
+//! Top-level device configuration and the builder classes register themselves on.
+
+use crate::control::{OutResponse, Request};
+use crate::descriptor::DescriptorWriter;
+use crate::driver::Driver;
+use crate::types::{InterfaceNumber, StringIndex};
+use crate::UsbDevice;
+
+/// Static, user-supplied description of the device, written into the device descriptor.
+pub struct Config<'a> {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub device_class: u8,
+    pub device_sub_class: u8,
+    pub device_protocol: u8,
+    pub max_packet_size_0: u8,
+    pub manufacturer: Option<&'a str>,
+    pub product: Option<&'a str>,
+    pub serial_number: Option<&'a str>,
+    pub self_powered: bool,
+    pub supports_remote_wakeup: bool,
+    pub max_power: u8,
+}
+
+impl<'a> Config<'a> {
+    pub fn new(vendor_id: u16, product_id: u16) -> Self {
+        Config {
+            vendor_id,
+            product_id,
+            device_class: 0,
+            device_sub_class: 0,
+            device_protocol: 0,
+            max_packet_size_0: 8,
+            manufacturer: None,
+            product: None,
+            serial_number: None,
+            self_powered: false,
+            supports_remote_wakeup: false,
+            max_power: 50,
+        }
+    }
+}
+
+/// Callbacks for device-level (as opposed to class-level) bus events.
+///
+/// Implement this to track connection/configuration state or manage power budget.
+/// `UsbDevice` stores the handler passed to the builder but does not call any of
+/// these methods: it receives no bus events from the driver.
+pub trait DeviceStateHandler {
+    /// Called when the host resets the bus.
+    fn reset(&self) {}
+    /// Called when the device receives a `SET_ADDRESS` request.
+    fn addressed(&self, _addr: u8) {}
+    /// Called when the host configures (or unconfigures) the device.
+    fn configured(&self, _configured: bool) {}
+    /// Called when the bus is suspended or resumed.
+    fn suspended(&self, _suspended: bool) {}
+    /// Called when the device is disabled.
+    fn disabled(&self) {}
+}
+
+/// Handles a control request addressed to one interface (class, vendor, or an
+/// interface-recipient standard request such as a HID report `GET_DESCRIPTOR`).
+///
+/// Classes implement this and register it with the builder. The `UsbDevice`
+/// control loop offers every interface-recipient request to each registered
+/// handler in turn; each handler claims only requests for its own interface number.
+pub trait Handler {
+    /// Handles an OUT control request (host-to-device), returning `None` if this
+    /// handler isn't the right one for the given request.
+    fn control_out(&mut self, _req: Request, _data: &[u8]) -> Option<OutResponse> {
+        None
+    }
+
+    /// Handles an IN control request (device-to-host) by writing the response into
+    /// `buf` and returning the number of bytes written, or `None` if this handler
+    /// isn't the right one for the given request.
+    fn control_in<'a>(&'a mut self, _req: Request, _buf: &'a mut [u8]) -> Option<usize> {
+        None
+    }
+}
+
+/// Maximum number of classes that can be registered on one `UsbDeviceBuilder`.
+pub const MAX_HANDLERS: usize = 4;
+
+/// Builds a [`UsbDevice`] by registering one or more classes, each of which
+/// allocates interfaces, endpoints and strings, and writes its own descriptors.
+pub struct UsbDeviceBuilder<'d, D: Driver<'d>> {
+    pub(crate) driver: D,
+    pub(crate) config: Config<'d>,
+    pub(crate) device_descriptor: DescriptorWriter<'d>,
+    pub(crate) config_descriptor: DescriptorWriter<'d>,
+    pub(crate) bos_descriptor: DescriptorWriter<'d>,
+    pub(crate) control_buf: &'d mut [u8],
+    pub(crate) device_state_handler: Option<&'d dyn DeviceStateHandler>,
+    pub(crate) next_interface_number: u8,
+    pub(crate) next_string_index: u8,
+    pub(crate) handlers: heapless::Vec<&'d mut dyn Handler, MAX_HANDLERS>,
+    pub(crate) msos_descriptor: Option<(u8, [u8; 256], usize)>,
+}
+
+impl<'d, D: Driver<'d>> UsbDeviceBuilder<'d, D> {
+    /// Creates a new builder.
+    pub fn new(
+        driver: D,
+        config: Config<'d>,
+        device_descriptor_buf: &'d mut [u8],
+        config_descriptor_buf: &'d mut [u8],
+        bos_descriptor_buf: &'d mut [u8],
+        control_buf: &'d mut [u8],
+        device_state_handler: Option<&'d dyn DeviceStateHandler>,
+    ) -> Self {
+        let mut config_descriptor = DescriptorWriter::new(config_descriptor_buf);
+        let attributes = 0x80 // reserved, must be set
+            | if config.self_powered { 0x40 } else { 0 }
+            | if config.supports_remote_wakeup { 0x20 } else { 0 };
+        config_descriptor.configuration(1, attributes, config.max_power);
+
+        let mut bos_descriptor = DescriptorWriter::new(bos_descriptor_buf);
+        bos_descriptor.bos();
+
+        UsbDeviceBuilder {
+            driver,
+            config,
+            device_descriptor: DescriptorWriter::new(device_descriptor_buf),
+            config_descriptor,
+            bos_descriptor,
+            control_buf,
+            device_state_handler,
+            next_interface_number: 0,
+            next_string_index: 4, // 0 reserved, 1-3 used by manufacturer/product/serial
+            handlers: heapless::Vec::new(),
+            msos_descriptor: None,
+        }
+    }
+
+    /// Allocates the next free interface number.
+    pub fn alloc_interface(&mut self) -> InterfaceNumber {
+        let number = InterfaceNumber::new(self.next_interface_number);
+        self.next_interface_number += 1;
+        number
+    }
+
+    /// Allocates `interface_count` consecutive interface numbers for one function
+    /// and emits its Interface Association Descriptor ahead of them, so a host
+    /// binds a single driver to the whole function regardless of whether the
+    /// overall device ends up composite. Returns the first interface number;
+    /// the caller allocates the rest with further `alloc_interface` calls.
+    pub fn alloc_interface_association(
+        &mut self,
+        interface_count: u8,
+        function_class: u8,
+        function_sub_class: u8,
+        function_protocol: u8,
+    ) -> InterfaceNumber {
+        let first = self.alloc_interface();
+        self.config_descriptor
+            .iad(first, interface_count, function_class, function_sub_class, function_protocol);
+        first
+    }
+
+    /// Allocates a string index for a class-owned string descriptor.
+    pub fn alloc_string(&mut self) -> StringIndex {
+        let index = StringIndex::new(self.next_string_index);
+        self.next_string_index += 1;
+        index
+    }
+
+    /// Gives mutable access to the configuration descriptor being assembled.
+    pub fn config_descriptor(&mut self) -> &mut DescriptorWriter<'d> {
+        &mut self.config_descriptor
+    }
+
+    /// Gives mutable access to the driver, for allocating endpoints.
+    pub fn driver(&mut self) -> &mut D {
+        &mut self.driver
+    }
+
+    /// Registers a Microsoft OS 2.0 descriptor set, advertised via a BOS platform
+    /// capability descriptor, so Windows auto-binds WinUSB to `set`'s function with
+    /// no INF file. `vendor_code` is the vendor-specific `bRequest` Windows will use
+    /// to fetch the descriptor set with `GET_MS_OS_20_DESCRIPTOR`; pick any value
+    /// that doesn't collide with another vendor request this device answers.
+    pub fn msos_descriptor(&mut self, set: crate::msos::MsOsDescriptorSet, vendor_code: u8) {
+        let (bytes, len) = set.finish();
+        crate::msos::write_platform_capability(&mut self.bos_descriptor, len as u16, vendor_code);
+        self.msos_descriptor = Some((vendor_code, bytes, len));
+    }
+
+    /// Registers a class's [`Handler`] so the control loop can route requests to it.
+    pub fn handler(&mut self, handler: &'d mut dyn Handler) {
+        self.handlers
+            .push(handler)
+            .ok()
+            .expect("too many classes registered on this UsbDeviceBuilder, raise MAX_HANDLERS");
+    }
+
+    /// Finishes building the device.
+    ///
+    /// If more than one class was registered, the device descriptor's class/sub-class/
+    /// protocol are switched to the USB-IF "multi-interface function" codes
+    /// (0xEF/0x02/0x01) so Windows picks a composite driver and binds each function
+    /// by its Interface Association Descriptor, regardless of what `Config` asked for.
+    pub fn build(mut self) -> UsbDevice<'d, D> {
+        if self.handlers.len() > 1 {
+            self.config.device_class = 0xEF;
+            self.config.device_sub_class = 0x02;
+            self.config.device_protocol = 0x01;
+        }
+
+        self.write_device_descriptor();
+
+        let device_descriptor = self.device_descriptor.finish();
+        let config_descriptor = self.config_descriptor.finish_with_total_length();
+        let bos_descriptor = self.bos_descriptor.finish_with_total_length();
+
+        let control_pipe = self.driver.start(self.config.max_packet_size_0 as u16);
+        UsbDevice::new(
+            control_pipe,
+            device_descriptor,
+            config_descriptor,
+            bos_descriptor,
+            self.control_buf,
+            self.config.manufacturer,
+            self.config.product,
+            self.config.serial_number,
+            self.device_state_handler,
+            self.handlers,
+            self.msos_descriptor,
+        )
+    }
+
+    fn write_device_descriptor(&mut self) {
+        let c = &self.config;
+        self.device_descriptor.write(
+            crate::descriptor::descriptor_type::DEVICE,
+            &[
+                0x00,
+                0x02, // bcdUSB 2.00
+                c.device_class,
+                c.device_sub_class,
+                c.device_protocol,
+                c.max_packet_size_0,
+                c.vendor_id as u8,
+                (c.vendor_id >> 8) as u8,
+                c.product_id as u8,
+                (c.product_id >> 8) as u8,
+                0x00,
+                0x01, // bcdDevice 1.00
+                if c.manufacturer.is_some() { 1 } else { 0 },
+                if c.product.is_some() { 2 } else { 0 },
+                if c.serial_number.is_some() { 3 } else { 0 },
+                1, // bNumConfigurations
+            ],
+        );
+    }
+}
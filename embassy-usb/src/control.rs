@@ -0,0 +1,58 @@
+//! Control request types shared by `UsbDevice` and the classes it hosts.
+
+/// Control request type, as encoded in `bmRequestType`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RequestType {
+    Standard = 0,
+    Class = 1,
+    Vendor = 2,
+    Reserved = 3,
+}
+
+/// Control request recipient, as encoded in `bmRequestType`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Recipient {
+    Device = 0,
+    Interface = 1,
+    Endpoint = 2,
+    Other = 3,
+}
+
+/// A parsed `SETUP` packet.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Request {
+    pub direction: UsbDirection,
+    pub request_type: RequestType,
+    pub recipient: Recipient,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    pub length: u16,
+}
+
+/// Direction of a control or data transfer, matching `bmRequestType` bit 7.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UsbDirection {
+    Out = 0,
+    In = 0x80,
+}
+
+/// Response to an OUT control request (host-to-device).
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OutResponse {
+    Accepted,
+    Rejected,
+}
+
+/// Response to an IN control request (device-to-host).
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum InResponse<'a> {
+    Accepted(&'a [u8]),
+    Rejected,
+}
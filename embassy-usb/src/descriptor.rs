@@ -0,0 +1,260 @@
+//! Helpers for building USB configuration descriptors incrementally.
+//!
+//! Classes never poke bytes directly; they call into a [`DescriptorWriter`]
+//! handed to them by the `UsbDeviceBuilder`, which keeps interface numbering
+//! and the IAD bookkeeping in one place (endpoint addresses come from the driver).
+
+use crate::types::InterfaceNumber;
+
+/// Standard descriptor type codes (USB 2.0 table 9-5).
+pub mod descriptor_type {
+    pub const DEVICE: u8 = 1;
+    pub const CONFIGURATION: u8 = 2;
+    pub const STRING: u8 = 3;
+    pub const INTERFACE: u8 = 4;
+    pub const ENDPOINT: u8 = 5;
+    pub const BOS: u8 = 15;
+    pub const DEVICE_CAPABILITY: u8 = 16;
+    pub const INTERFACE_ASSOCIATION: u8 = 11;
+    pub const CS_INTERFACE: u8 = 0x24;
+    pub const CS_ENDPOINT: u8 = 0x25;
+}
+
+/// Incrementally writes a configuration (or BOS) descriptor into a fixed buffer.
+///
+/// A class is given one of these while it's being registered on the builder, and
+/// writes its interface, endpoint, and class-specific descriptors into it in order.
+pub struct DescriptorWriter<'a> {
+    buf: &'a mut [u8],
+    position: usize,
+    /// Offset of the `bNumInterfaces` byte in a `configuration()` header, bumped
+    /// by every `interface()`/`interface_alt()` call.
+    num_interfaces_mark: Option<usize>,
+    /// Offset of the `bNumEndpoints` byte in the interface descriptor most
+    /// recently written by `interface()`/`interface_alt()`, bumped by `endpoint()`.
+    num_endpoints_mark: Option<usize>,
+    /// Offset of the `bNumDeviceCaps` byte in a `bos()` header, bumped by every
+    /// `write(DEVICE_CAPABILITY, ..)` call.
+    num_device_caps_mark: Option<usize>,
+}
+
+impl<'a> DescriptorWriter<'a> {
+    pub(crate) fn new(buf: &'a mut [u8]) -> Self {
+        DescriptorWriter {
+            buf,
+            position: 0,
+            num_interfaces_mark: None,
+            num_endpoints_mark: None,
+            num_device_caps_mark: None,
+        }
+    }
+
+    /// Number of bytes written so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Writes a single raw descriptor: `bLength`, `bDescriptorType`, then `data`.
+    pub fn write(&mut self, descriptor_type: u8, data: &[u8]) {
+        let len = data.len() + 2;
+        assert!(len <= 255, "descriptor too long");
+        self.write_bytes(&[len as u8, descriptor_type]);
+        self.write_bytes(data);
+        if descriptor_type == descriptor_type::DEVICE_CAPABILITY {
+            if let Some(mark) = self.num_device_caps_mark {
+                self.buf[mark] += 1;
+            }
+        }
+    }
+
+    fn write_bytes(&mut self, data: &[u8]) {
+        let end = self.position + data.len();
+        assert!(end <= self.buf.len(), "descriptor buffer overflow");
+        self.buf[self.position..end].copy_from_slice(data);
+        self.position = end;
+    }
+
+    fn patch_u16_at(&mut self, at: usize, v: u16) {
+        self.buf[at..at + 2].copy_from_slice(&v.to_le_bytes());
+    }
+
+    /// Writes the configuration descriptor header; must be called once, before
+    /// any class writes its interface/endpoint descriptors, so `wTotalLength`
+    /// and `bNumInterfaces` land at the front of the buffer where the host
+    /// expects the configuration descriptor to start.
+    pub(crate) fn configuration(&mut self, config_value: u8, attributes: u8, max_power: u8) {
+        self.write_bytes(&[
+            9,
+            descriptor_type::CONFIGURATION,
+            0,
+            0, // wTotalLength, patched in `finish_with_total_length`
+            0, // bNumInterfaces, bumped as interface descriptors are written
+            config_value,
+            0, // iConfiguration
+            attributes,
+            max_power,
+        ]);
+        self.num_interfaces_mark = Some(self.position - 5);
+    }
+
+    /// Writes the BOS descriptor header; must be called once, before any
+    /// device capability is written.
+    pub(crate) fn bos(&mut self) {
+        self.write_bytes(&[
+            5,
+            descriptor_type::BOS,
+            0,
+            0, // wTotalLength, patched in `finish_with_total_length`
+            0, // bNumDeviceCaps, bumped as capabilities are written
+        ]);
+        self.num_device_caps_mark = Some(self.position - 1);
+    }
+
+    /// Patches `wTotalLength` (always at offset 2 of a CONFIGURATION or BOS
+    /// header) to the final length, and returns the completed descriptor.
+    pub(crate) fn finish_with_total_length(mut self) -> &'a [u8] {
+        let total_len = self.position as u16;
+        self.patch_u16_at(2, total_len);
+        self.finish()
+    }
+
+    /// Returns the descriptor written so far, with no further patching.
+    pub(crate) fn finish(self) -> &'a [u8] {
+        let DescriptorWriter { buf, position, .. } = self;
+        &buf[..position]
+    }
+
+    /// Writes an Interface Association Descriptor grouping `interface_count`
+    /// consecutive interfaces starting at `first_interface`.
+    pub fn iad(
+        &mut self,
+        first_interface: InterfaceNumber,
+        interface_count: u8,
+        function_class: u8,
+        function_sub_class: u8,
+        function_protocol: u8,
+    ) {
+        self.write_bytes(&[
+            8,
+            descriptor_type::INTERFACE_ASSOCIATION,
+            first_interface.0,
+            interface_count,
+            function_class,
+            function_sub_class,
+            function_protocol,
+            0, // iFunction
+        ]);
+    }
+
+    /// Writes a standard interface descriptor.
+    pub fn interface(
+        &mut self,
+        number: InterfaceNumber,
+        alt_setting: u8,
+        class: u8,
+        sub_class: u8,
+        protocol: u8,
+    ) {
+        self.interface_alt(number, alt_setting, class, sub_class, protocol, None);
+    }
+
+    /// Writes a standard interface descriptor, optionally naming it via a string index.
+    pub fn interface_alt(
+        &mut self,
+        number: InterfaceNumber,
+        alt_setting: u8,
+        class: u8,
+        sub_class: u8,
+        protocol: u8,
+        interface_string: Option<u8>,
+    ) {
+        self.write_bytes(&[
+            9,
+            descriptor_type::INTERFACE,
+            number.0,
+            alt_setting,
+            0, // bNumEndpoints, bumped by `endpoint()` below
+            class,
+            sub_class,
+            protocol,
+            interface_string.unwrap_or(0),
+        ]);
+        self.num_endpoints_mark = Some(self.position - 5);
+        if let Some(mark) = self.num_interfaces_mark {
+            self.buf[mark] += 1;
+        }
+    }
+
+    /// Writes a class-specific ("CS_INTERFACE") functional descriptor.
+    pub fn write_cs_interface(&mut self, subtype: u8, data: &[u8]) {
+        let mut buf = [0u8; 64];
+        assert!(data.len() + 1 <= buf.len());
+        buf[0] = subtype;
+        buf[1..=data.len()].copy_from_slice(data);
+        self.write(descriptor_type::CS_INTERFACE, &buf[..=data.len()]);
+    }
+
+    /// Writes a standard endpoint descriptor.
+    pub fn endpoint(&mut self, address: u8, ep_type: u8, max_packet_size: u16, interval: u8) {
+        self.write_bytes(&[
+            7,
+            descriptor_type::ENDPOINT,
+            address,
+            ep_type,
+            max_packet_size as u8,
+            (max_packet_size >> 8) as u8,
+            interval,
+        ]);
+        if let Some(mark) = self.num_endpoints_mark {
+            self.buf[mark] += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configuration_total_length_covers_every_interface_and_endpoint() {
+        let mut buf = [0u8; 64];
+        let mut writer = DescriptorWriter::new(&mut buf);
+        writer.configuration(1, 0x80, 50);
+        writer.interface(InterfaceNumber(0), 0, 0xff, 0, 0);
+        writer.endpoint(0x81, 0x02, 64, 0);
+
+        let out = writer.finish_with_total_length();
+
+        assert_eq!(out[0], 9, "configuration bLength");
+        assert_eq!(out[1], descriptor_type::CONFIGURATION);
+        assert_eq!(u16::from_le_bytes([out[2], out[3]]), out.len() as u16, "wTotalLength");
+        assert_eq!(out[4], 1, "bNumInterfaces must count the one interface() call");
+
+        let iface = &out[9..];
+        assert_eq!(iface[0], 9, "interface bLength");
+        assert_eq!(iface[1], descriptor_type::INTERFACE);
+        assert_eq!(iface[4], 1, "bNumEndpoints must count the one endpoint() call");
+
+        let ep = &out[18..];
+        assert_eq!(ep[0], 7, "endpoint bLength");
+        assert_eq!(ep[1], descriptor_type::ENDPOINT);
+        assert_eq!(out.len(), 9 + 9 + 7);
+    }
+
+    #[test]
+    fn bos_total_length_and_device_cap_count_track_every_write() {
+        let mut buf = [0u8; 64];
+        let mut writer = DescriptorWriter::new(&mut buf);
+        writer.bos();
+        writer.write(descriptor_type::DEVICE_CAPABILITY, &[0u8; 4]);
+        writer.write(descriptor_type::DEVICE_CAPABILITY, &[0u8; 2]);
+
+        let out = writer.finish_with_total_length();
+
+        assert_eq!(out[0], 5, "BOS bLength");
+        assert_eq!(out[1], descriptor_type::BOS);
+        assert_eq!(u16::from_le_bytes([out[2], out[3]]), out.len() as u16, "wTotalLength");
+        assert_eq!(out[4], 2, "bNumDeviceCaps must count both write() calls");
+        assert_eq!(out.len(), 5 + 6 + 4);
+    }
+}
@@ -0,0 +1,124 @@
+//! Traits a hardware abstraction layer implements to plug into `embassy-usb`.
+//!
+//! `embassy_nrf::usb::Driver` and friends implement these; the builder and
+//! classes in this crate only ever talk to the peripheral through them.
+
+use crate::control::Request;
+
+/// Implemented by a HAL to expose a USB peripheral to `embassy-usb`.
+///
+/// The builder and classes allocate endpoints through this trait while the
+/// descriptors are being assembled; `alloc_endpoint_in`/`alloc_endpoint_out`
+/// return handles good for the lifetime of the device.
+pub trait Driver<'d> {
+    type EndpointOut: EndpointOut;
+    type EndpointIn: EndpointIn;
+    type ControlPipe: ControlPipe;
+
+    /// Allocates an IN endpoint of the given type and maximum packet size.
+    fn alloc_endpoint_in(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval: u8,
+    ) -> Result<Self::EndpointIn, EndpointError>;
+
+    /// Allocates an OUT endpoint of the given type and maximum packet size.
+    fn alloc_endpoint_out(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval: u8,
+    ) -> Result<Self::EndpointOut, EndpointError>;
+
+    /// Starts the peripheral and returns its control pipe.
+    fn start(self, control_max_packet_size: u16) -> Self::ControlPipe;
+}
+
+/// Errors returned by endpoint operations.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EndpointError {
+    BufferOverflow,
+    Disabled,
+}
+
+/// Endpoint transfer type, as encoded in the endpoint descriptor.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EndpointType {
+    Control,
+    Isochronous,
+    Bulk,
+    Interrupt,
+}
+
+/// An endpoint address: the endpoint number in the low nibble, plus bit 7 set
+/// for an IN (device-to-host) endpoint.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EndpointAddress(u8);
+
+impl EndpointAddress {
+    pub const fn from_parts(index: usize, dir_in: bool) -> Self {
+        EndpointAddress(index as u8 | if dir_in { 0x80 } else { 0 })
+    }
+
+    pub fn index(&self) -> usize {
+        (self.0 & 0x0f) as usize
+    }
+
+    pub fn is_in(&self) -> bool {
+        self.0 & 0x80 != 0
+    }
+}
+
+impl From<u8> for EndpointAddress {
+    fn from(addr: u8) -> Self {
+        EndpointAddress(addr)
+    }
+}
+
+impl From<EndpointAddress> for u8 {
+    fn from(addr: EndpointAddress) -> Self {
+        addr.0
+    }
+}
+
+/// An in-direction (device-to-host) endpoint allocated by the driver.
+pub trait EndpointIn {
+    /// The address the driver allocated this endpoint at, for writing into
+    /// the endpoint descriptor the class emits alongside it.
+    fn address(&self) -> EndpointAddress;
+
+    /// Writes a single packet; `data.len()` must not exceed `max_packet_size`.
+    async fn write(&mut self, data: &[u8]) -> Result<(), EndpointError>;
+}
+
+/// An out-direction (host-to-device) endpoint allocated by the driver.
+pub trait EndpointOut {
+    /// The address the driver allocated this endpoint at, for writing into
+    /// the endpoint descriptor the class emits alongside it.
+    fn address(&self) -> EndpointAddress;
+
+    /// Reads a single packet into `data`, returning the number of bytes read.
+    async fn read(&mut self, data: &mut [u8]) -> Result<usize, EndpointError>;
+}
+
+/// The bidirectional control endpoint (endpoint 0).
+pub trait ControlPipe {
+    /// Waits for and returns the next `SETUP` packet.
+    async fn setup(&mut self) -> Request;
+
+    /// Sends `data` in response to an IN control request.
+    async fn accept_in(&mut self, data: &[u8]);
+
+    /// Accepts an OUT control request with no data stage.
+    async fn accept(&mut self);
+
+    /// Rejects the current control request (stalls endpoint 0).
+    async fn reject(&mut self);
+
+    /// Reads the data stage of an OUT control request into `buf`.
+    async fn data_out(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError>;
+}
@@ -0,0 +1,220 @@
+#![no_std]
+#![feature(type_alias_impl_trait)]
+
+//! Async USB device stack, built on top of a [`driver::Driver`] implemented by a HAL.
+//!
+//! A device is assembled with [`UsbDeviceBuilder`], which one or more classes
+//! (`embassy-usb-hid`, `embassy-usb-serial`, ...) register themselves on, and is
+//! then driven by awaiting [`UsbDevice::run`], which never returns.
+
+mod builder;
+pub mod control;
+pub mod descriptor;
+pub mod driver;
+pub mod msos;
+pub mod types;
+
+pub use builder::{Config, DeviceStateHandler, Handler, UsbDeviceBuilder};
+
+use control::{Recipient, Request, RequestType, UsbDirection};
+use descriptor::descriptor_type;
+use driver::{ControlPipe, Driver};
+
+/// `wIndex` Microsoft's spec reserves for the `GET_MS_OS_20_DESCRIPTOR` vendor request.
+const MS_OS_20_DESCRIPTOR_INDEX: u16 = 0x07;
+
+/// Standard `bRequest` for `GET_DESCRIPTOR` (USB 2.0 table 9-4).
+const REQ_GET_DESCRIPTOR: u8 = 0x06;
+
+/// The only language this stack's string descriptors are served in (US English).
+const LANGID_ENGLISH_US: u16 = 0x0409;
+
+/// The built, runnable USB device. Created via [`UsbDeviceBuilder::build`].
+pub struct UsbDevice<'d, D: Driver<'d>> {
+    control_pipe: D::ControlPipe,
+    device_descriptor: &'d [u8],
+    config_descriptor: &'d [u8],
+    bos_descriptor: &'d [u8],
+    control_buf: &'d mut [u8],
+    manufacturer: Option<&'d str>,
+    product: Option<&'d str>,
+    serial_number: Option<&'d str>,
+    device_state_handler: Option<&'d dyn DeviceStateHandler>,
+    handlers: heapless::Vec<&'d mut dyn Handler, { builder::MAX_HANDLERS }>,
+    msos_descriptor: Option<(u8, [u8; 256], usize)>,
+}
+
+impl<'d, D: Driver<'d>> UsbDevice<'d, D> {
+    pub(crate) fn new(
+        control_pipe: D::ControlPipe,
+        device_descriptor: &'d [u8],
+        config_descriptor: &'d [u8],
+        bos_descriptor: &'d [u8],
+        control_buf: &'d mut [u8],
+        manufacturer: Option<&'d str>,
+        product: Option<&'d str>,
+        serial_number: Option<&'d str>,
+        device_state_handler: Option<&'d dyn DeviceStateHandler>,
+        handlers: heapless::Vec<&'d mut dyn Handler, { builder::MAX_HANDLERS }>,
+        msos_descriptor: Option<(u8, [u8; 256], usize)>,
+    ) -> Self {
+        UsbDevice {
+            control_pipe,
+            device_descriptor,
+            config_descriptor,
+            bos_descriptor,
+            control_buf,
+            manufacturer,
+            product,
+            serial_number,
+            device_state_handler,
+            handlers,
+            msos_descriptor,
+        }
+    }
+
+    /// Runs the device forever, servicing control transfers: it answers device
+    /// `GET_DESCRIPTOR` and the MS OS 2.0 vendor request itself, offers every
+    /// interface-recipient request to the handlers registered on the builder, and
+    /// stalls anything else. Bus events (reset, suspend, resume) are not handled
+    /// here, and the builder's [`DeviceStateHandler`] is never called.
+    ///
+    /// This future never completes; join it with your class data futures.
+    pub async fn run(&mut self) -> ! {
+        loop {
+            let req = self.control_pipe.setup().await;
+            match req.direction {
+                UsbDirection::Out => self.handle_control_out(req).await,
+                UsbDirection::In => self.handle_control_in(req).await,
+            }
+        }
+    }
+
+    async fn handle_control_out(&mut self, req: Request) {
+        let data = if req.length == 0 {
+            &[][..]
+        } else {
+            match self.control_pipe.data_out(&mut *self.control_buf).await {
+                Ok(n) => &self.control_buf[..n],
+                Err(_) => {
+                    self.control_pipe.reject().await;
+                    return;
+                }
+            }
+        };
+
+        // Route to the first handler registered for the targeted interface; every
+        // class only answers for its own interface number, so at most one ever
+        // claims a given request.
+        for handler in self.handlers.iter_mut() {
+            if req.recipient != Recipient::Interface {
+                continue;
+            }
+            if let Some(response) = handler.control_out(req, data) {
+                match response {
+                    control::OutResponse::Accepted => self.control_pipe.accept().await,
+                    control::OutResponse::Rejected => self.control_pipe.reject().await,
+                }
+                return;
+            }
+        }
+
+        self.control_pipe.reject().await;
+    }
+
+    async fn handle_control_in(&mut self, req: Request) {
+        if req.request_type == RequestType::Standard
+            && req.recipient == Recipient::Device
+            && req.request == REQ_GET_DESCRIPTOR
+        {
+            self.handle_get_descriptor(req).await;
+            return;
+        }
+
+        if let Some((vendor_code, set, len)) = &self.msos_descriptor {
+            if req.request_type == RequestType::Vendor
+                && req.request == *vendor_code
+                && req.index == MS_OS_20_DESCRIPTOR_INDEX
+            {
+                self.control_pipe.accept_in(&set[..*len]).await;
+                return;
+            }
+        }
+
+        for handler in self.handlers.iter_mut() {
+            if req.recipient != Recipient::Interface {
+                continue;
+            }
+            if let Some(len) = handler.control_in(req, &mut *self.control_buf) {
+                self.control_pipe.accept_in(&self.control_buf[..len]).await;
+                return;
+            }
+        }
+
+        self.control_pipe.reject().await;
+    }
+
+    /// Serves a standard `GET_DESCRIPTOR` request (USB 2.0 section 9.4.3) for the
+    /// device, configuration, BOS and string descriptors assembled at `build()` time.
+    ///
+    /// Serving BOS matters beyond USB 2.0 compliance: it's how Windows discovers
+    /// the MS OS 2.0 platform capability `msos_descriptor()` wrote into it, which
+    /// is what lets WinUSB auto-bind with no INF file.
+    async fn handle_get_descriptor(&mut self, req: Request) {
+        let descriptor_type = (req.value >> 8) as u8;
+        let descriptor_index = req.value as u8;
+
+        match descriptor_type {
+            descriptor_type::DEVICE => self.control_pipe.accept_in(self.device_descriptor).await,
+            descriptor_type::CONFIGURATION => self.control_pipe.accept_in(self.config_descriptor).await,
+            descriptor_type::BOS => self.control_pipe.accept_in(self.bos_descriptor).await,
+            descriptor_type::STRING => match self.write_string_descriptor(descriptor_index) {
+                Some(len) => self.control_pipe.accept_in(&self.control_buf[..len]).await,
+                None => self.control_pipe.reject().await,
+            },
+            _ => self.control_pipe.reject().await,
+        }
+    }
+
+    /// Writes string descriptor `index` into `control_buf`, returning its length.
+    ///
+    /// Index 0 is the special language ID list (we only ever claim US English);
+    /// indices 1-3 are the manufacturer/product/serial number strings from `Config`,
+    /// UTF-16LE encoded as the descriptor requires. Truncates to whatever
+    /// `control_buf` can hold rather than panicking if a configured string doesn't
+    /// fit; size `control_buf` for the longest string you configure.
+    fn write_string_descriptor(&mut self, index: u8) -> Option<usize> {
+        if index == 0 {
+            if self.control_buf.len() < 4 {
+                return None;
+            }
+            self.control_buf[0] = 4;
+            self.control_buf[1] = descriptor_type::STRING;
+            self.control_buf[2..4].copy_from_slice(&LANGID_ENGLISH_US.to_le_bytes());
+            return Some(4);
+        }
+
+        let s = match index {
+            1 => self.manufacturer,
+            2 => self.product,
+            3 => self.serial_number,
+            _ => None,
+        }?;
+
+        if self.control_buf.len() < 2 {
+            return None;
+        }
+
+        let mut pos = 2;
+        for c in s.encode_utf16() {
+            if pos + 2 > self.control_buf.len() {
+                break;
+            }
+            self.control_buf[pos..pos + 2].copy_from_slice(&c.to_le_bytes());
+            pos += 2;
+        }
+        self.control_buf[0] = pos as u8;
+        self.control_buf[1] = descriptor_type::STRING;
+        Some(pos)
+    }
+}
@@ -0,0 +1,245 @@
+//! Microsoft OS 2.0 descriptors.
+//!
+//! These let a device advertise, from a BOS platform capability descriptor plus a
+//! vendor-request-served descriptor set, that Windows should bind WinUSB (or another
+//! in-box driver) to one of its functions without an INF file. See Microsoft's
+//! "Microsoft OS 2.0 Descriptors Specification".
+
+/// `{D8DD60DF-4589-4CC7-9CD2-659D9E648A9F}`, little-endian as it appears on the wire.
+const MS_OS_20_PLATFORM_CAPABILITY_UUID: [u8; 16] = [
+    0xdf, 0x60, 0xdd, 0xd8, 0x89, 0x45, 0xc7, 0x4c, 0x9c, 0xd2, 0x65, 0x9d, 0x9e, 0x64, 0x8a, 0x9f,
+];
+
+/// Windows build targeted by `dwWindowsVersion`; 8.1 is the minimum that supports
+/// MS OS 2.0 descriptors and is what Microsoft's own samples use.
+pub const WINDOWS_VERSION_8_1: u32 = 0x06_03_00_00;
+
+mod descriptor_type {
+    pub const SET_HEADER_DESCRIPTOR: u16 = 0x00;
+    pub const SUBSET_HEADER_CONFIGURATION: u16 = 0x01;
+    pub const SUBSET_HEADER_FUNCTION: u16 = 0x02;
+    pub const FEATURE_COMPATIBLE_ID: u16 = 0x03;
+    pub const FEATURE_REG_PROPERTY: u16 = 0x04;
+}
+
+/// `wPropertyDataType` values (Microsoft OS 2.0 Descriptors spec, table 14).
+const REG_MULTI_SZ: u16 = 0x07;
+
+/// Writes a BOS platform capability descriptor pointing at an MS OS 2.0 descriptor
+/// set, which the device must serve from a vendor `GET_MS_OS_20_DESCRIPTOR` request
+/// (`bRequest == vendor_code`, `wIndex == 0x07`) using the bytes from [`MsOsDescriptorSet::finish`].
+///
+/// Register this once via [`crate::UsbDeviceBuilder::msos_descriptor`].
+pub(crate) fn write_platform_capability(
+    bos: &mut crate::descriptor::DescriptorWriter<'_>,
+    descriptor_set_len: u16,
+    vendor_code: u8,
+) {
+    let mut data = [0u8; 24];
+    data[0..16].copy_from_slice(&MS_OS_20_PLATFORM_CAPABILITY_UUID);
+    data[16..20].copy_from_slice(&WINDOWS_VERSION_8_1.to_le_bytes());
+    data[20..22].copy_from_slice(&descriptor_set_len.to_le_bytes());
+    data[22] = vendor_code;
+    data[23] = 0; // bAltEnumCode: no alternate enumeration
+
+    // Platform device capability: bDevCapabilityType(PLATFORM=5), bReserved, then data.
+    let mut full = [0u8; 26];
+    full[0] = 0x05; // PLATFORM
+    full[1] = 0x00; // bReserved
+    full[2..].copy_from_slice(&data);
+    bos.write(crate::descriptor::descriptor_type::DEVICE_CAPABILITY, &full);
+}
+
+/// One function's worth of MS OS 2.0 feature descriptors (compatible ID + registry
+/// properties), built up into a fixed buffer and served byte-for-byte in response to
+/// `GET_MS_OS_20_DESCRIPTOR`.
+///
+/// Only a single configuration/function subset is supported, which covers the common
+/// case of one vendor-class device advertising one WinUSB-compatible function.
+pub struct MsOsDescriptorSet {
+    buf: [u8; 256],
+    len: usize,
+    /// Byte offsets of the `wTotalLength`/`wSubsetLength` fields left to patch once
+    /// every feature descriptor has been pushed: `[set, configuration, function]`.
+    pending_lengths: [usize; 3],
+}
+
+impl MsOsDescriptorSet {
+    /// Starts a descriptor set for `first_interface`'s function, with `compatible_id`
+    /// zero-padded/truncated to 8 bytes (e.g. `"WINUSB"`).
+    pub fn new(first_interface: u8, compatible_id: &str) -> Self {
+        let mut set = MsOsDescriptorSet {
+            buf: [0; 256],
+            len: 0,
+            pending_lengths: [0; 3],
+        };
+
+        // Set header (patched below once we know the total length).
+        set.push_u16(0x0a);
+        set.push_u16(descriptor_type::SET_HEADER_DESCRIPTOR);
+        set.push_u32(WINDOWS_VERSION_8_1);
+        let total_len_at = set.len;
+        set.push_u16(0); // wTotalLength, patched in `finish`
+
+        // Configuration subset header (single configuration, written as index 0).
+        set.push_u16(0x08);
+        set.push_u16(descriptor_type::SUBSET_HEADER_CONFIGURATION);
+        set.push_u8(0); // bConfigurationValue: configuration index 0
+        set.push_u8(0); // bReserved
+        let config_len_at = set.len;
+        set.push_u16(0); // wTotalLength, patched in `finish`
+
+        // Function subset header.
+        set.push_u16(0x08);
+        set.push_u16(descriptor_type::SUBSET_HEADER_FUNCTION);
+        set.push_u8(first_interface);
+        set.push_u8(0); // bReserved
+        let function_len_at = set.len;
+        set.push_u16(0); // wSubsetLength, patched in `finish`
+
+        // Compatible ID feature descriptor.
+        set.push_u16(0x14);
+        set.push_u16(descriptor_type::FEATURE_COMPATIBLE_ID);
+        set.push_padded_ascii(compatible_id, 8);
+        set.push_padded_ascii("", 8); // sub-compatible ID: unused
+
+        set.pending_lengths = [total_len_at, config_len_at, function_len_at];
+        set
+    }
+
+    /// Adds a `DeviceInterfaceGUIDs` registry property (a `REG_MULTI_SZ`) naming
+    /// `guid` (e.g. `"{12345678-1234-1234-1234-123456789abc}"`), so Windows creates a
+    /// device interface firmware can be opened through without any driver install.
+    pub fn device_interface_guids(mut self, guid: &str) -> Self {
+        let name = "DeviceInterfaceGUIDs";
+        let name_utf16_len = (name.len() + 1) * 2; // + NUL
+        let data_utf16_len = (guid.len() + 2) * 2; // + NUL + second NUL (REG_MULTI_SZ)
+
+        let desc_len = 2 + 2 + 2 + 2 + name_utf16_len + 2 + data_utf16_len;
+        self.push_u16(desc_len as u16);
+        self.push_u16(descriptor_type::FEATURE_REG_PROPERTY);
+        self.push_u16(REG_MULTI_SZ);
+        self.push_u16(name_utf16_len as u16);
+        self.push_utf16_nul(name);
+        self.push_u16(data_utf16_len as u16);
+        self.push_utf16_nul(guid);
+        self.push_u16(0); // second NUL terminating the REG_MULTI_SZ list
+
+        self
+    }
+
+    /// Finalizes the set, patching the length fields now that every feature
+    /// descriptor has been pushed, and returns the bytes to serve verbatim in
+    /// response to `GET_MS_OS_20_DESCRIPTOR`.
+    pub fn finish(mut self) -> ([u8; 256], usize) {
+        let total_len = self.len as u16;
+        let [total_len_at, config_len_at, function_len_at] = self.pending_lengths;
+        self.patch_u16(total_len_at, total_len);
+        self.patch_u16(config_len_at, total_len - 10); // everything after the set header
+        self.patch_u16(function_len_at, total_len - 10 - 8); // everything after both headers
+        (self.buf, self.len)
+    }
+
+    fn push_u8(&mut self, v: u8) {
+        self.buf[self.len] = v;
+        self.len += 1;
+    }
+
+    fn push_u16(&mut self, v: u16) {
+        self.buf[self.len..self.len + 2].copy_from_slice(&v.to_le_bytes());
+        self.len += 2;
+    }
+
+    fn push_u32(&mut self, v: u32) {
+        self.buf[self.len..self.len + 4].copy_from_slice(&v.to_le_bytes());
+        self.len += 4;
+    }
+
+    fn push_padded_ascii(&mut self, s: &str, width: usize) {
+        let bytes = s.as_bytes();
+        let n = bytes.len().min(width);
+        self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+        self.len += width;
+    }
+
+    fn push_utf16_nul(&mut self, s: &str) {
+        for c in s.encode_utf16() {
+            self.buf[self.len..self.len + 2].copy_from_slice(&c.to_le_bytes());
+            self.len += 2;
+        }
+        self.len += 2; // NUL terminator
+    }
+
+    fn patch_u16(&mut self, at: usize, v: u16) {
+        self.buf[at..at + 2].copy_from_slice(&v.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u16(buf: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([buf[at], buf[at + 1]])
+    }
+
+    #[test]
+    fn set_header_and_lengths_cover_the_whole_set() {
+        let (buf, len) = MsOsDescriptorSet::new(0, "WINUSB").finish();
+
+        // Set header: wLength=0x0a, wDescriptorType=SET_HEADER_DESCRIPTOR, dwWindowsVersion, wTotalLength.
+        assert_eq!(read_u16(&buf, 0), 0x0a);
+        assert_eq!(read_u16(&buf, 2), descriptor_type::SET_HEADER_DESCRIPTOR);
+        assert_eq!(read_u16(&buf, 8), len as u16, "wTotalLength must cover every byte written");
+
+        // Configuration subset header starts right after the 10-byte set header.
+        assert_eq!(read_u16(&buf, 10), 0x08);
+        assert_eq!(read_u16(&buf, 12), descriptor_type::SUBSET_HEADER_CONFIGURATION);
+        assert_eq!(
+            read_u16(&buf, 16),
+            len as u16 - 10,
+            "configuration wTotalLength must cover everything after the set header"
+        );
+
+        // Function subset header starts right after the 8-byte configuration header.
+        assert_eq!(read_u16(&buf, 18), 0x08);
+        assert_eq!(read_u16(&buf, 20), descriptor_type::SUBSET_HEADER_FUNCTION);
+        assert_eq!(buf[22], 0, "bFirstInterface");
+        assert_eq!(
+            read_u16(&buf, 24),
+            len as u16 - 10 - 8,
+            "function wSubsetLength must cover everything after both headers"
+        );
+
+        // Compatible ID feature descriptor immediately follows: wLength=0x14, type, "WINUSB" padded to 8.
+        assert_eq!(read_u16(&buf, 26), 0x14);
+        assert_eq!(read_u16(&buf, 28), descriptor_type::FEATURE_COMPATIBLE_ID);
+        assert_eq!(&buf[30..36], b"WINUSB");
+        assert_eq!(&buf[36..38], &[0, 0]);
+
+        assert_eq!(len, 26 + 0x14);
+    }
+
+    #[test]
+    fn device_interface_guids_extends_the_total_and_subset_lengths() {
+        let guid = "{12345678-1234-1234-1234-123456789abc}";
+        let base_len = MsOsDescriptorSet::new(0, "WINUSB").finish().1;
+        let (buf, len) = MsOsDescriptorSet::new(0, "WINUSB").device_interface_guids(guid).finish();
+
+        let name = "DeviceInterfaceGUIDs";
+        let name_utf16_len = (name.len() + 1) * 2;
+        let data_utf16_len = (guid.len() + 2) * 2;
+        let desc_len = 2 + 2 + 2 + 2 + name_utf16_len + 2 + data_utf16_len;
+
+        assert_eq!(len, base_len + desc_len, "registry property descriptor must append its own length");
+        assert_eq!(read_u16(&buf, base_len), desc_len as u16);
+        assert_eq!(read_u16(&buf, base_len + 2), descriptor_type::FEATURE_REG_PROPERTY);
+        assert_eq!(read_u16(&buf, base_len + 4), REG_MULTI_SZ);
+        assert_eq!(read_u16(&buf, base_len + 6), name_utf16_len as u16);
+
+        // wTotalLength/wSubsetLength must grow to include the new descriptor too.
+        assert_eq!(read_u16(&buf, 8), len as u16);
+        assert_eq!(read_u16(&buf, 16), len as u16 - 10);
+        assert_eq!(read_u16(&buf, 24), len as u16 - 10 - 8);
+    }
+}
@@ -0,0 +1,46 @@
+//! Descriptor-relative identifiers handed out by the `UsbDeviceBuilder`.
+
+/// Handle for a USB interface, assigned during configuration descriptor construction.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InterfaceNumber(pub(crate) u8);
+
+impl InterfaceNumber {
+    pub(crate) fn new(index: u8) -> Self {
+        InterfaceNumber(index)
+    }
+}
+
+impl From<InterfaceNumber> for u8 {
+    fn from(n: InterfaceNumber) -> Self {
+        n.0
+    }
+}
+
+impl Default for InterfaceNumber {
+    fn default() -> Self {
+        InterfaceNumber(0)
+    }
+}
+
+/// Handle for a USB interface's alternate setting.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InterfaceAltSetting(pub(crate) u8);
+
+/// Handle for a USB string descriptor, assigned during configuration descriptor construction.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StringIndex(pub(crate) u8);
+
+impl StringIndex {
+    pub(crate) fn new(index: u8) -> Self {
+        StringIndex(index)
+    }
+}
+
+impl From<StringIndex> for u8 {
+    fn from(i: StringIndex) -> Self {
+        i.0
+    }
+}
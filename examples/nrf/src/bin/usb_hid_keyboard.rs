@@ -6,10 +6,7 @@
 use core::mem;
 use core::sync::atomic::{AtomicBool, Ordering};
 use defmt::*;
-use embassy::blocking_mutex::raw::CriticalSectionRawMutex;
-use embassy::channel::Channel;
 use embassy::executor::Spawner;
-use embassy::interrupt::InterruptExt;
 use embassy::time::Duration;
 use embassy_nrf::gpio::{Input, Pin, Pull};
 use embassy_nrf::interrupt;
@@ -17,41 +14,21 @@ use embassy_nrf::pac;
 use embassy_nrf::usb::Driver;
 use embassy_nrf::Peripherals;
 use embassy_usb::control::OutResponse;
-use embassy_usb::{Config, DeviceCommand, DeviceStateHandler, UsbDeviceBuilder};
-use embassy_usb_hid::{HidClass, ReportId, RequestHandler, State};
+use embassy_usb::msos::MsOsDescriptorSet;
+use embassy_usb::{Config, DeviceStateHandler, UsbDeviceBuilder};
+use embassy_usb_dfu::{DfuDetachHandler, DfuRuntimeClass};
+use embassy_usb_hid::{HidClass, HidProtocol, ReportId, RequestHandler, State};
+use embassy_usb_serial::{CdcAcmClass, CdcAcmState};
 use futures::future::join;
+use futures::future::join3;
 use usbd_hid::descriptor::{KeyboardReport, SerializedDescriptor};
 
 use defmt_rtt as _; // global logger
 use panic_probe as _;
 
-static USB_COMMANDS: Channel<CriticalSectionRawMutex, DeviceCommand, 1> = Channel::new();
-static SUSPENDED: AtomicBool = AtomicBool::new(false);
-
-fn on_power_interrupt(_: *mut ()) {
-    let regs = unsafe { &*pac::POWER::ptr() };
-
-    if regs.events_usbdetected.read().bits() != 0 {
-        regs.events_usbdetected.reset();
-        info!("Vbus detected, enabling USB...");
-        if USB_COMMANDS.try_send(DeviceCommand::Enable).is_err() {
-            warn!("Failed to send enable command to USB channel");
-        }
-    }
-
-    if regs.events_usbremoved.read().bits() != 0 {
-        regs.events_usbremoved.reset();
-        info!("Vbus removed, disabling USB...");
-        if USB_COMMANDS.try_send(DeviceCommand::Disable).is_err() {
-            warn!("Failed to send disable command to USB channel");
-        };
-    }
-}
-
 #[embassy::main]
 async fn main(_spawner: Spawner, p: Peripherals) {
     let clock: pac::CLOCK = unsafe { mem::transmute(()) };
-    let power: pac::POWER = unsafe { mem::transmute(()) };
 
     info!("Enabling ext hfosc...");
     clock.tasks_hfclkstart.write(|w| unsafe { w.bits(1) });
@@ -69,20 +46,22 @@ async fn main(_spawner: Spawner, p: Peripherals) {
     config.max_power = 100;
     config.max_packet_size_0 = 64;
     config.supports_remote_wakeup = true;
-    config.start_enabled = false;
 
     // Create embassy-usb DeviceBuilder using the driver and config.
-    // It needs some buffers for building the descriptors.
+    // It needs some buffers for building the descriptors. `control_buf` also
+    // has to be big enough for the biggest thing served out of it directly:
+    // the longest configured string descriptor and the HID report descriptor.
     let mut device_descriptor = [0; 256];
     let mut config_descriptor = [0; 256];
     let mut bos_descriptor = [0; 256];
-    let mut control_buf = [0; 16];
+    let mut control_buf = [0; 64];
     let request_handler = MyRequestHandler {};
     let device_state_handler = MyDeviceStateHandler::new();
 
-    let mut state = State::<8, 1>::new();
+    let mut state = State::<1>::new();
+    let mut serial_state = CdcAcmState::new();
 
-    let mut builder = UsbDeviceBuilder::new_with_channel(
+    let mut builder = UsbDeviceBuilder::new(
         driver,
         config,
         &mut device_descriptor,
@@ -90,11 +69,14 @@ async fn main(_spawner: Spawner, p: Peripherals) {
         &mut bos_descriptor,
         &mut control_buf,
         Some(&device_state_handler),
-        &USB_COMMANDS,
     );
 
-    // Create classes on the builder.
-    let hid = HidClass::with_output_ep(
+    // Create classes on the builder. Registering more than one, as here, makes this
+    // a composite device: interface numbers, endpoints and strings are all
+    // auto-allocated, and the builder emits the Interface Association Descriptors
+    // (and switches the device class to the multi-function codes) the host needs
+    // to bind a driver to each function independently.
+    let (hid, mut hid_handler) = HidClass::with_output_ep(
         &mut builder,
         &mut state,
         KeyboardReport::desc(),
@@ -102,6 +84,25 @@ async fn main(_spawner: Spawner, p: Peripherals) {
         60,
         64,
     );
+    let (serial, mut serial_handler) = CdcAcmClass::new(&mut builder, &mut serial_state, 64);
+
+    // Let a host tool like dfu-util ask us to drop into the bootloader, without
+    // needing a separate DFU-mode-only build or an external programmer.
+    let dfu_handler_impl = MyDfuDetachHandler {};
+    let mut dfu_handler = DfuRuntimeClass::new(&mut builder, Some(&dfu_handler_impl), 2500, 4096);
+
+    // Advertise a Microsoft OS 2.0 descriptor set for the serial function so
+    // Windows binds WinUSB to it with no INF file, letting host tooling open it
+    // directly alongside (or instead of) the usbser.sys COM port.
+    builder.msos_descriptor(
+        MsOsDescriptorSet::new(serial.first_interface().into(), "WINUSB")
+            .device_interface_guids("{975F44D9-0D08-43FD-8B3E-127CA8AFFF9D}"),
+        0x20,
+    );
+
+    builder.handler(&mut hid_handler);
+    builder.handler(&mut serial_handler);
+    builder.handler(&mut dfu_handler);
 
     // Build the builder.
     let mut usb = builder.build();
@@ -112,6 +113,7 @@ async fn main(_spawner: Spawner, p: Peripherals) {
     let mut button = Input::new(p.P0_11.degrade(), Pull::Up);
 
     let (mut hid_in, hid_out) = hid.split();
+    let (mut serial_tx, mut serial_rx) = serial.split();
 
     // Do stuff with the class!
     let in_fut = async {
@@ -119,11 +121,10 @@ async fn main(_spawner: Spawner, p: Peripherals) {
             button.wait_for_low().await;
             info!("PRESSED");
 
-            if SUSPENDED.load(Ordering::Acquire) {
-                info!("Triggering remote wakeup");
-                USB_COMMANDS.send(DeviceCommand::RemoteWakeup);
-            }
-
+            // KeyboardReport is already the fixed 8-byte boot report layout, so
+            // both HID protocols send identical bytes here; we still read it so
+            // firmware with a richer report-protocol layout can branch on it.
+            info!("Sending in {:?} protocol", hid_in.protocol());
             let report = KeyboardReport {
                 keycodes: [4, 0, 0, 0, 0, 0],
                 leds: 0,
@@ -137,6 +138,7 @@ async fn main(_spawner: Spawner, p: Peripherals) {
 
             button.wait_for_high().await;
             info!("RELEASED");
+            info!("Sending in {:?} protocol", hid_in.protocol());
             let report = KeyboardReport {
                 keycodes: [0, 0, 0, 0, 0, 0],
                 leds: 0,
@@ -154,18 +156,26 @@ async fn main(_spawner: Spawner, p: Peripherals) {
         hid_out.run(false, &request_handler).await;
     };
 
-    let power_irq = interrupt::take!(POWER_CLOCK);
-    power_irq.set_handler(on_power_interrupt);
-    power_irq.unpend();
-    power_irq.enable();
-
-    power
-        .intenset
-        .write(|w| w.usbdetected().set().usbremoved().set());
+    // Echo whatever the host's serial terminal sends back at it, once it's
+    // actually opened the port (DTR asserted).
+    let serial_fut = async {
+        loop {
+            let mut buf = [0; 64];
+            match serial_rx.read_packet(&mut buf).await {
+                Ok(n) if serial_tx.dtr() => {
+                    if let Err(e) = serial_tx.write_packet(&buf[..n]).await {
+                        warn!("Failed to echo serial data: {:?}", e);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Failed to read serial data: {:?}", e),
+            }
+        }
+    };
 
     // Run everything concurrently.
     // If we had made everything `'static` above instead, we could do this using separate tasks instead.
-    join(usb_fut, join(in_fut, out_fut)).await;
+    join(usb_fut, join3(in_fut, out_fut, serial_fut)).await;
 }
 
 struct MyRequestHandler {}
@@ -189,6 +199,18 @@ impl RequestHandler for MyRequestHandler {
         info!("Get idle rate for {:?}", id);
         None
     }
+
+    fn set_protocol(&self, protocol: HidProtocol) {
+        info!("Host selected {:?} protocol", protocol);
+    }
+}
+
+struct MyDfuDetachHandler {}
+
+impl DfuDetachHandler for MyDfuDetachHandler {
+    fn detach(&self) {
+        info!("Host requested DFU detach (this example only logs it, it does not reset)");
+    }
 }
 
 struct MyDeviceStateHandler {
@@ -228,16 +250,10 @@ impl DeviceStateHandler for MyDeviceStateHandler {
     fn suspended(&self, suspended: bool) {
         if suspended {
             info!("Device suspended, the Vbus current limit is 500µA (or 2.5mA for high-power devices with remote wakeup enabled).");
-            SUSPENDED.store(true, Ordering::Release);
+        } else if self.configured.load(Ordering::Relaxed) {
+            info!("Device resumed, it may now draw up to the configured current limit from Vbus");
         } else {
-            SUSPENDED.store(false, Ordering::Release);
-            if self.configured.load(Ordering::Relaxed) {
-                info!(
-                    "Device resumed, it may now draw up to the configured current limit from Vbus"
-                );
-            } else {
-                info!("Device resumed, the Vbus current limit is 100mA");
-            }
+            info!("Device resumed, the Vbus current limit is 100mA");
         }
     }
 